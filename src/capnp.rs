@@ -1,17 +1,127 @@
+use std::collections::{BTreeMap, HashMap};
+
 use anyhow::{bail, Context, Result};
-use lsp_types::{Diagnostic, DiagnosticSeverity, Range, Url};
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, Range, Url,
+};
+
+use crate::config::Config;
+
+/// How confidently a [`Suggestion`] can be applied without a human reviewing it first.
+///
+/// Mirrors rustfix's `Applicability`: `MachineApplicable` fixes are safe to apply
+/// automatically (e.g. "fix all in file"), while `MaybeIncorrect` fixes are syntactically
+/// correct but may change behavior in a way the user should confirm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applicability {
+    MachineApplicable,
+    MaybeIncorrect,
+}
+
+/// A fix for a single [`Diagnostic`]: replace the text at `range` with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub range: Range,
+    pub replacement: String,
+    pub applicability: Applicability,
+}
 
-pub fn diags(uri: &Url, proto_paths: &Vec<std::path::PathBuf>) -> Result<Vec<Diagnostic>> {
+/// The diagnostics found while compiling a file, grouped by the file they belong to (`capnp
+/// compile` reports errors in imported files too, not just the file being compiled), plus any
+/// fixes for them (each with its [`Applicability`]), keyed by the range of the diagnostic they
+/// address.
+///
+/// Only `PartialEq`, not `Eq`: `lsp_types::Diagnostic` carries a `data: Option<serde_json::Value>`
+/// field, and `serde_json::Value` isn't `Eq`.
+#[derive(Debug, Default, PartialEq)]
+pub struct DiagResult {
+    pub diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    pub suggestions: HashMap<Url, HashMap<Range, Suggestion>>,
+}
+
+/// Compiles the on-disk file at `uri` and returns its diagnostics.
+pub fn diags(uri: &Url, config: &Config) -> Result<DiagResult> {
     if uri.scheme() != "file" {
         bail!("Unsupported URI scheme {uri}");
     }
+    let Ok(path) = uri.to_file_path() else {
+        bail!("Failed to normalize URI path: {uri}");
+    };
 
+    let proto_paths = config.proto_paths(&path);
+    compile(&path, &proto_paths, &config.capnp_path)
+}
+
+/// Compiles `text` as if it were saved at `uri`, without touching the real file. The buffer is
+/// written to a uniquely-named temp file in the *same directory* as the real file, and any
+/// diagnostics reported against that temp file are remapped back onto `uri` before returning.
+///
+/// The temp file has to live alongside the real one rather than in some scratch directory:
+/// `capnp` resolves a relative (non-`/`-prefixed) import like `import "bar.capnp"` against the
+/// directory of the importing file itself, not against `-I`/`proto_paths` (those only apply to
+/// absolute imports). Compiling the buffer from an unrelated directory would make every relative
+/// import fail, which is the common case for a multi-file schema.
+///
+/// Unlike the `tempfile` usage elsewhere in this crate, this runs on every unsaved edit, not just
+/// in tests, so `tempfile` needs to be a regular dependency rather than a dev-dependency.
+pub fn diags_unsaved(uri: &Url, text: &str, config: &Config) -> Result<DiagResult> {
+    use std::io::Write;
+
+    if uri.scheme() != "file" {
+        bail!("Unsupported URI scheme {uri}");
+    }
     let Ok(path) = uri.to_file_path() else {
         bail!("Failed to normalize URI path: {uri}");
     };
+    let dir = path
+        .parent()
+        .with_context(|| format!("No parent directory for {path:?}"))?;
+
+    let proto_paths = config.proto_paths(&path);
+
+    let mut tmp_file = tempfile::Builder::new()
+        .prefix(".capnls-")
+        .suffix(".capnp")
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create a temp file alongside {path:?}"))?;
+    tmp_file
+        .write_all(text.as_bytes())
+        .with_context(|| format!("Failed to write unsaved buffer to {:?}", tmp_file.path()))?;
+    let tmp_uri = Url::from_file_path(tmp_file.path())
+        .map_err(|()| anyhow::anyhow!("Failed to build a file URL from {:?}", tmp_file.path()))?;
+
+    let mut result = compile(tmp_file.path(), &proto_paths, &config.capnp_path)?;
+    remap_uri(&mut result, &tmp_uri, uri);
+    Ok(result)
+}
 
-    let mut cmd = std::process::Command::new("capnp");
-    let path = path
+/// Rewrites every occurrence of `from` (a `Url`) in `result` to `to`.
+fn remap_uri(result: &mut DiagResult, from: &Url, to: &Url) {
+    if let Some(diagnostics) = result.diagnostics.remove(from) {
+        result.diagnostics.insert(to.clone(), diagnostics);
+    }
+    if let Some(suggestions) = result.suggestions.remove(from) {
+        result.suggestions.insert(to.clone(), suggestions);
+    }
+    for diagnostics in result.diagnostics.values_mut() {
+        for diag in diagnostics {
+            for related in diag.related_information.iter_mut().flatten() {
+                if &related.location.uri == from {
+                    related.location.uri = to.clone();
+                }
+            }
+        }
+    }
+}
+
+/// Runs `capnp compile` over `path` and parses its stderr into a [`DiagResult`].
+fn compile(
+    path: &std::path::Path,
+    proto_paths: &[std::path::PathBuf],
+    capnp_path: &std::path::Path,
+) -> Result<DiagResult> {
+    let mut cmd = std::process::Command::new(capnp_path);
+    let path_str = path
         .to_str()
         .with_context(|| format!("Non-unicode path: {path:?}"))?;
     cmd.arg("compile")
@@ -28,7 +138,7 @@ pub fn diags(uri: &Url, proto_paths: &Vec<std::path::PathBuf>) -> Result<Vec<Dia
                 .map(|p| "-I".to_string() + p),
         )
         // Add the file we're compiling
-        .arg(path);
+        .arg(path_str);
 
     log::debug!("Running capnp: {cmd:?}");
     let output = cmd.output()?;
@@ -36,26 +146,264 @@ pub fn diags(uri: &Url, proto_paths: &Vec<std::path::PathBuf>) -> Result<Vec<Dia
     log::debug!("Capnp exited: {output:?}");
     let stderr = std::str::from_utf8(output.stderr.as_slice())?;
 
-    let res = stderr.lines().filter_map(|l| parse_diag(l)).collect();
-    log::trace!("Generated diagnostics: {res:?}");
-    Ok(res)
+    // `capnp` reports imported files by a name relative to whichever `-I` root they were found
+    // under, so resolving them needs at least one search root even if the caller configured
+    // none: fall back to the directory of the file we're compiling, same as `capnp` itself does
+    // when no `-I` is given.
+    let mut search_roots = proto_paths.to_vec();
+    if let Some(dir) = path.parent() {
+        if !search_roots.iter().any(|p| p == dir) {
+            search_roots.push(dir.to_path_buf());
+        }
+    }
+
+    let mut diagnostics: HashMap<Url, Vec<Diagnostic>> = HashMap::new();
+    for (file_uri, diag) in collect_diagnostics(stderr, &search_roots) {
+        diagnostics.entry(file_uri).or_default().push(diag);
+    }
+    log::trace!("Generated diagnostics: {diagnostics:?}");
+
+    // Suggestions need each file's source text to know what to replace, so compute them in a
+    // second pass rather than threading the source through `parse_diag`.
+    let mut suggestions: HashMap<Url, HashMap<Range, Suggestion>> = HashMap::new();
+    for (file_uri, file_diagnostics) in &diagnostics {
+        let Ok(file_path) = file_uri.to_file_path() else {
+            continue;
+        };
+        let source = match std::fs::read_to_string(&file_path) {
+            Ok(source) => source,
+            Err(e) => {
+                log::warn!("Failed to read {file_path:?} for suggestions: {e}");
+                continue;
+            }
+        };
+        let file_suggestions = file_diagnostics
+            .iter()
+            .filter_map(|diag| Some((diag.range, suggestion_for(&source, diag)?)))
+            .collect();
+        suggestions.insert(file_uri.clone(), file_suggestions);
+    }
+
+    Ok(DiagResult {
+        diagnostics,
+        suggestions,
+    })
+}
+
+/// Computes a fix for `diag`, if we recognize its message.
+fn suggestion_for(source: &str, diag: &Diagnostic) -> Option<Suggestion> {
+    if diag
+        .message
+        .starts_with("Cap'n Proto declaration names should use camelCase")
+    {
+        camel_case_suggestion(source, diag)
+    } else if diag.message == "Duplicate ordinal number" {
+        duplicate_ordinal_suggestion(source, diag)
+    } else {
+        None
+    }
+}
+
+/// Suggests renaming the identifier underlined by `diag` to camelCase.
+fn camel_case_suggestion(source: &str, diag: &Diagnostic) -> Option<Suggestion> {
+    let line = source.lines().nth(diag.range.start.line as usize)?;
+    let start = diag.range.start.character as usize;
+    let end = diag.range.end.character as usize;
+    let ident = line.get(start..end)?;
+
+    Some(Suggestion {
+        range: diag.range,
+        replacement: to_camel_case(ident),
+        applicability: Applicability::MachineApplicable,
+    })
+}
+
+fn to_camel_case(ident: &str) -> String {
+    let mut out = String::new();
+    let mut capitalize_next = false;
+    for c in ident.chars() {
+        if c == '_' {
+            capitalize_next = true;
+        } else if capitalize_next {
+            out.extend(c.to_uppercase());
+            capitalize_next = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Suggests renumbering the ordinal underlined by `diag` to the next one unused in its
+/// enclosing struct.
+fn duplicate_ordinal_suggestion(source: &str, diag: &Diagnostic) -> Option<Suggestion> {
+    let (start, end) = enclosing_block(source, diag.range.start.line)?;
+    let next = next_free_ordinal(&source[start..end]);
+
+    Some(Suggestion {
+        range: diag.range,
+        replacement: next.to_string(),
+        applicability: Applicability::MaybeIncorrect,
+    })
+}
+
+/// Finds the byte range of the innermost `{ ... }` block containing `line`.
+fn enclosing_block(source: &str, line: u32) -> Option<(usize, usize)> {
+    let mut target = None;
+    let mut offset = 0;
+    for (i, l) in source.split_inclusive('\n').enumerate() {
+        if i as u32 == line {
+            target = Some(offset);
+            break;
+        }
+        offset += l.len();
+    }
+    let target = target?;
+
+    let mut open_braces = Vec::new();
+    let mut enclosing: Option<(usize, usize)> = None;
+    for (i, c) in source.char_indices() {
+        match c {
+            '{' => open_braces.push(i),
+            '}' => {
+                let Some(start) = open_braces.pop() else {
+                    continue;
+                };
+                let is_tighter = match enclosing {
+                    Some((best, _)) => start > best,
+                    None => true,
+                };
+                if start < target && i > target && is_tighter {
+                    enclosing = Some((start, i));
+                }
+            }
+            _ => {}
+        }
+    }
+    enclosing
+}
+
+/// Scans a struct body for the ordinals already in use and returns the smallest unused one.
+fn next_free_ordinal(block: &str) -> u32 {
+    let mut used = std::collections::HashSet::new();
+    let bytes = block.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b != b'@' {
+            continue;
+        }
+        // Skip constant IDs like `@0xeb77878e33236528`.
+        if bytes.get(i + 1) == Some(&b'0') && bytes.get(i + 2) == Some(&b'x') {
+            continue;
+        }
+        let digits: String = block[i + 1..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        if let Ok(n) = digits.parse::<u32>() {
+            used.insert(n);
+        }
+    }
+    (0..).find(|n| !used.contains(n)).unwrap()
+}
+
+/// Parses every diagnostic out of a `capnp compile` stderr stream, folding "originally used
+/// here" hints into the `related_information` of the duplicate-ordinal error they explain
+/// instead of emitting them as standalone diagnostics.
+///
+/// Duplicate-ordinal errors are buffered in a `BTreeMap` keyed by the stderr line they came from
+/// until their hint arrives (mirroring how rustc's borrow checker buffers related move errors
+/// before emitting them), so the association still works if unrelated diagnostics are
+/// interleaved between the error and its hint.
+fn collect_diagnostics(stderr: &str, proto_paths: &[std::path::PathBuf]) -> Vec<(Url, Diagnostic)> {
+    let mut diagnostics: Vec<(Url, Diagnostic)> = Vec::new();
+    // Duplicate-ordinal errors not yet paired with their hint, keyed by the stderr line they
+    // were parsed from.
+    let mut pending_duplicates: BTreeMap<usize, usize> = BTreeMap::new();
+
+    for (i, line) in stderr.lines().enumerate() {
+        let Some((file_uri, diag)) = parse_diag(line, proto_paths) else {
+            continue;
+        };
+
+        if diag.message.ends_with("originally used here") {
+            if let Some((&key, &diag_idx)) = pending_duplicates.range(..i).next_back() {
+                if diagnostics[diag_idx].0 == file_uri {
+                    pending_duplicates.remove(&key);
+                    diagnostics[diag_idx]
+                        .1
+                        .related_information
+                        .get_or_insert_with(Vec::new)
+                        .push(DiagnosticRelatedInformation {
+                            location: Location {
+                                uri: file_uri,
+                                range: diag.range,
+                            },
+                            message: diag.message,
+                        });
+                    continue;
+                }
+            }
+        }
+
+        if diag.message == "Duplicate ordinal number" {
+            pending_duplicates.insert(i, diagnostics.len());
+        }
+        diagnostics.push((file_uri, diag));
+    }
+
+    diagnostics
 }
 
-// Parse a single error line from the capnp parser into a diagnostic.
+/// Resolves a filename as reported by `capnp compile` to an absolute `Url`.
+///
+/// `capnp` reports imported files relative to whichever `-I` root they were found under, so we
+/// re-resolve against the same `proto_paths` to figure out which one. Falls back to the first
+/// configured root if the file can't be found on disk (e.g. an unsaved buffer).
+fn resolve_uri(filename: &str, proto_paths: &[std::path::PathBuf]) -> Option<Url> {
+    let path = std::path::Path::new(filename);
+    if path.is_absolute() {
+        return Url::from_file_path(path).ok();
+    }
+    for root in proto_paths {
+        let candidate = root.join(path);
+        if candidate.exists() {
+            return Url::from_file_path(candidate).ok();
+        }
+    }
+    let root = proto_paths.first()?;
+    Url::from_file_path(root.join(path)).ok()
+}
+
+/// The pieces of a single capnp stderr line, before the filename has been resolved to a `Url`.
+struct DiagLine<'a> {
+    filename: &'a str,
+    line: u32,
+    col_start: u32,
+    col_end: u32,
+    message: &'a str,
+}
+
+// Splits a single error line from the capnp parser into its filename, position and message.
 // Lines look like:
 // foo.capnp:3:9: error: Parse error.
-fn parse_diag(diag: &str) -> Option<lsp_types::Diagnostic> {
-    let (_, rest) = diag.split_once(':')?;
-    let (lineno, rest) = rest.split_once(':')?;
-    let (colno, rest) = rest.split_once(':')?;
-    let msg = rest.strip_prefix(" error: ")?.trim().trim_end_matches(".");
+//
+// We anchor the parse on the trailing `:line:col[-col]: error: ` grammar and scan from the
+// right rather than splitting on the first `:`, since the filename itself may contain colons
+// (e.g. a Windows path like `C:\proto\foo.capnp`).
+fn split_diag_line(diag: &str) -> Option<DiagLine<'_>> {
+    let error_idx = diag.rfind(": error: ")?;
+    let message = diag[error_idx + ": error: ".len()..]
+        .trim()
+        .trim_end_matches(".");
+    let (rest, colno) = diag[..error_idx].rsplit_once(':')?;
+    let (filename, lineno) = rest.rsplit_once(':')?;
 
     // Lines from capnp stderr are 1-indexed.
-    let lineno = lineno.parse::<u32>().unwrap().saturating_sub(1);
+    let line = lineno.parse::<u32>().ok()?.saturating_sub(1);
     let (col_start, col_end) = match colno.split_once('-') {
-        Some((start, end)) => (start.parse::<u32>().unwrap(), end.parse::<u32>().unwrap()),
+        Some((start, end)) => (start.parse::<u32>().ok()?, end.parse::<u32>().ok()?),
         None => {
-            let start = colno.parse::<u32>().unwrap();
+            let start = colno.parse::<u32>().ok()?;
             (start, start)
         }
     };
@@ -63,49 +411,42 @@ fn parse_diag(diag: &str) -> Option<lsp_types::Diagnostic> {
     let col_start = col_start.saturating_sub(1);
     let col_end = col_end.saturating_sub(1);
 
-    Some(lsp_types::Diagnostic {
+    Some(DiagLine {
+        filename,
+        line,
+        col_start,
+        col_end,
+        message,
+    })
+}
+
+// Parses a single error line from the capnp parser into a diagnostic and the file it belongs to.
+fn parse_diag(diag: &str, proto_paths: &[std::path::PathBuf]) -> Option<(Url, lsp_types::Diagnostic)> {
+    let parsed = split_diag_line(diag)?;
+    let uri = resolve_uri(parsed.filename, proto_paths)?;
+
+    let diagnostic = lsp_types::Diagnostic {
         range: Range {
             start: lsp_types::Position {
-                line: lineno,
-                character: col_start.try_into().ok()?,
+                line: parsed.line,
+                character: parsed.col_start,
             },
             end: lsp_types::Position {
-                line: lineno,
-                character: col_end.try_into().ok()?,
+                line: parsed.line,
+                character: parsed.col_end,
             },
         },
-        severity: Some(if msg.ends_with("originally used here") {
+        severity: Some(if parsed.message.ends_with("originally used here") {
             DiagnosticSeverity::HINT
         } else {
             DiagnosticSeverity::ERROR
         }),
         source: Some(String::from("capnls")),
-        message: msg.into(),
+        message: parsed.message.into(),
         ..Default::default()
-    })
-}
+    };
 
-#[test]
-fn test_parse_diag() {
-    assert_eq!(
-        parse_diag("foo.capnp:32:9: error: Parse error.",),
-        Some(lsp_types::Diagnostic {
-            range: Range {
-                start: lsp_types::Position {
-                    line: 31,
-                    character: 8,
-                },
-                end: lsp_types::Position {
-                    line: 31,
-                    character: 8,
-                },
-            },
-            severity: Some(DiagnosticSeverity::ERROR),
-            source: Some(String::from("capnls")),
-            message: "Parse error".into(),
-            ..Default::default()
-        })
-    )
+    Some((uri, diagnostic))
 }
 
 #[cfg(test)]
@@ -140,9 +481,14 @@ mod tests {
             ],
         );
 
-        let diags = diags(&uri, &vec![tmp.path().to_path_buf()]).unwrap();
+        let config = Config {
+            import_paths: vec![tmp.path().to_path_buf()],
+            auto_discover: false,
+            ..Config::default()
+        };
+        let result = diags(&uri, &config).unwrap();
 
-        let expected = [
+        let expected_diagnostics = [
             Diagnostic {
                 range: Range {
                     start: Position {
@@ -173,22 +519,22 @@ mod tests {
                 severity: Some(DiagnosticSeverity::ERROR),
                 source: Some("capnls".into()),
                 message: "Duplicate ordinal number".into(),
-                ..Default::default()
-            },
-            Diagnostic {
-                range: Range {
-                    start: Position {
-                        line: 2,
-                        character: 3,
+                related_information: Some(vec![lsp_types::DiagnosticRelatedInformation {
+                    location: lsp_types::Location {
+                        uri: uri.clone(),
+                        range: Range {
+                            start: Position {
+                                line: 2,
+                                character: 3,
+                            },
+                            end: Position {
+                                line: 2,
+                                character: 4,
+                            },
+                        },
                     },
-                    end: Position {
-                        line: 2,
-                        character: 4,
-                    },
-                },
-                severity: Some(DiagnosticSeverity::HINT),
-                source: Some("capnls".into()),
-                message: "Ordinal @0 originally used here".into(),
+                    message: "Ordinal @0 originally used here".into(),
+                }]),
                 ..Default::default()
             },
             Diagnostic {
@@ -208,6 +554,176 @@ mod tests {
                 ..Default::default()
             },
         ];
-        assert_eq!(diags, expected);
+        assert_eq!(
+            result.diagnostics.get(&uri),
+            Some(&expected_diagnostics.to_vec())
+        );
+
+        let file_suggestions = result.suggestions.get(&uri).unwrap();
+
+        let camel_case_fix = file_suggestions.get(&expected_diagnostics[0].range).unwrap();
+        assert_eq!(camel_case_fix.replacement, "oneTwo");
+        assert_eq!(camel_case_fix.applicability, Applicability::MachineApplicable);
+
+        let ordinal_fix = file_suggestions.get(&expected_diagnostics[1].range).unwrap();
+        assert_eq!(ordinal_fix.replacement, "3");
+        assert_eq!(ordinal_fix.applicability, Applicability::MaybeIncorrect);
+    }
+
+    #[test]
+    fn test_parse_diag() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (uri, _) = capnp_file(&tmp, "foo.capnp", &[""; 32]);
+        let proto_paths = vec![tmp.path().to_path_buf()];
+
+        assert_eq!(
+            parse_diag("foo.capnp:32:9: error: Parse error.", &proto_paths),
+            Some((
+                uri,
+                Diagnostic {
+                    range: Range {
+                        start: Position {
+                            line: 31,
+                            character: 8,
+                        },
+                        end: Position {
+                            line: 31,
+                            character: 8,
+                        },
+                    },
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some(String::from("capnls")),
+                    message: "Parse error".into(),
+                    ..Default::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_split_diag_line_windows_path() {
+        // A Windows path contains colons of its own (the drive letter), so the parser must
+        // anchor on the trailing `:line:col: error:` grammar rather than splitting on the
+        // first `:` in the line.
+        let parsed = split_diag_line(r"C:\proto\foo.capnp:3:9: error: Parse error.").unwrap();
+        assert_eq!(parsed.filename, r"C:\proto\foo.capnp");
+        assert_eq!(parsed.line, 2);
+        assert_eq!(parsed.col_start, 8);
+        assert_eq!(parsed.message, "Parse error");
+    }
+
+    #[test]
+    fn test_relative_import_resolves_without_proto_paths() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let tmp = tempfile::tempdir().unwrap();
+
+        capnp_file(
+            &tmp,
+            "bar.capnp",
+            &["@0xfa1ce000deadbeef;", "struct Bar {", "x_y @0 :Int32;", "}"],
+        );
+        let (uri, _) = capnp_file(
+            &tmp,
+            "foo.capnp",
+            &[
+                "@0xeb77878e33236528;",
+                r#"using Bar = import "bar.capnp".Bar;"#,
+                "struct Foo {",
+                "b @0 :Bar;",
+                "}",
+            ],
+        );
+
+        // No import_paths and auto-discovery off, so `capnp compile` is invoked with no `-I` at
+        // all: the only way to resolve `bar.capnp` (reported relative to whichever root it was
+        // found under) is to fall back to the directory of the file being compiled.
+        let config = Config {
+            auto_discover: false,
+            ..Config::default()
+        };
+        let result = diags(&uri, &config).unwrap();
+
+        let bar_uri = Url::from_file_path(tmp.path().join("bar.capnp")).unwrap();
+        let bar_diagnostics = result
+            .diagnostics
+            .get(&bar_uri)
+            .expect("diagnostic for the imported file should not be silently dropped");
+        assert!(bar_diagnostics
+            .iter()
+            .any(|d| d.message.contains("camelCase")));
+    }
+
+    #[test]
+    fn test_diags_unsaved_remaps_to_original_uri() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let tmp = tempfile::tempdir().unwrap();
+
+        let (uri, _) = capnp_file(
+            &tmp,
+            "foo.capnp",
+            &["@0xeb77878e33236528;", "struct Foo {", "}"],
+        );
+
+        // The saved file is fine, but the unsaved buffer has a parse error.
+        let config = Config {
+            import_paths: vec![tmp.path().to_path_buf()],
+            auto_discover: false,
+            ..Config::default()
+        };
+        let unsaved_text = "@0xeb77878e33236528;\nstruct Foo {\n";
+        let result = diags_unsaved(&uri, unsaved_text, &config).unwrap();
+
+        assert!(
+            result.diagnostics.contains_key(&uri),
+            "diagnostics should be keyed by the original uri, not the temp file: {:?}",
+            result.diagnostics.keys().collect::<Vec<_>>()
+        );
+        assert!(diags(&uri, &config).unwrap().diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_diags_unsaved_resolves_relative_imports() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let tmp = tempfile::tempdir().unwrap();
+
+        capnp_file(
+            &tmp,
+            "bar.capnp",
+            &["@0xfa1ce000deadbeef;", "struct Bar {", "x @0 :Int32;", "}"],
+        );
+        let (uri, _) = capnp_file(
+            &tmp,
+            "foo.capnp",
+            &[
+                "@0xeb77878e33236528;",
+                r#"using Bar = import "bar.capnp".Bar;"#,
+                "struct Foo {",
+                "b @0 :Bar;",
+                "}",
+            ],
+        );
+
+        // The temp file the unsaved buffer is compiled from must land next to `foo.capnp` (not
+        // some unrelated scratch directory), or this relative import fails to resolve on every
+        // keystroke.
+        let config = Config::default();
+        let unsaved_text = [
+            "@0xeb77878e33236528;",
+            r#"using Bar = import "bar.capnp".Bar;"#,
+            "struct Foo {",
+            "b @0 :Bar;",
+            "c @1 :Int32;",
+            "}",
+        ]
+        .join("\n")
+            + "\n";
+        let result = diags_unsaved(&uri, &unsaved_text, &config).unwrap();
+
+        assert_eq!(
+            result.diagnostics.get(&uri),
+            None,
+            "expected no errors, got: {:?}",
+            result.diagnostics.get(&uri)
+        );
     }
 }
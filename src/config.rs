@@ -0,0 +1,149 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// Directory entries that mark the root of a capnp workspace when auto-discovering import
+/// paths: seeing one of these in a directory stops the upward walk.
+const ROOT_MARKERS: &[&str] = &[".git", ".capnp-root"];
+
+/// Upper bound on how many ancestor directories [`discover_roots`] will walk before giving up.
+/// Without this, a tree with no [`ROOT_MARKERS`] anywhere above it (e.g. a schema opened outside
+/// any workspace) would walk all the way to the filesystem root, handing `capnp` directories like
+/// `/` or `/home` as `-I` search paths.
+const MAX_DISCOVER_DEPTH: usize = 16;
+
+/// User-configurable settings, read from the LSP `initializationOptions` and kept up to date via
+/// `workspace/didChangeConfiguration`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Path to the `capnp` executable. Defaults to `capnp`, resolved via `PATH`.
+    pub capnp_path: PathBuf,
+    /// Extra `-I` import directories, searched before any auto-discovered ones.
+    pub import_paths: Vec<PathBuf>,
+    /// When true, auto-discover import directories by walking up from each opened file to its
+    /// workspace root.
+    pub auto_discover: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            capnp_path: PathBuf::from("capnp"),
+            import_paths: Vec::new(),
+            auto_discover: true,
+        }
+    }
+}
+
+impl Config {
+    /// Resolves the de-duplicated, ordered set of `-I` directories to pass to `capnp` when
+    /// compiling `path`: the configured `import_paths`, followed by any directories
+    /// auto-discovered by walking up from `path` to its workspace root.
+    pub fn proto_paths(&self, path: &Path) -> Vec<PathBuf> {
+        let mut paths = self.import_paths.clone();
+
+        if self.auto_discover {
+            for dir in discover_roots(path) {
+                if !paths.contains(&dir) {
+                    paths.push(dir);
+                }
+            }
+        }
+
+        paths
+    }
+}
+
+/// Walks up from `path`'s parent directory, collecting every directory up to and including the
+/// first one containing a [`ROOT_MARKERS`] entry, or up to [`MAX_DISCOVER_DEPTH`] directories if
+/// none is found.
+fn discover_roots(path: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut dir = path.parent();
+
+    while let Some(d) = dir {
+        if dirs.len() >= MAX_DISCOVER_DEPTH {
+            log::warn!(
+                "No {ROOT_MARKERS:?} found within {MAX_DISCOVER_DEPTH} directories above \
+                 {path:?}; stopping auto-discovery there instead of walking to the filesystem root"
+            );
+            break;
+        }
+        dirs.push(d.to_path_buf());
+        if ROOT_MARKERS.iter().any(|marker| d.join(marker).exists()) {
+            break;
+        }
+        dir = d.parent();
+    }
+
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover_roots_stops_at_marker() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(root.join("proto/nested")).unwrap();
+        let file = root.join("proto/nested/foo.capnp");
+        std::fs::write(&file, "").unwrap();
+
+        let config = Config::default();
+        let paths = config.proto_paths(&file);
+
+        assert_eq!(
+            paths,
+            vec![
+                root.join("proto/nested"),
+                root.join("proto"),
+                root.to_path_buf(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_paths_come_first_and_dedup() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        let file = root.join("foo.capnp");
+        std::fs::write(&file, "").unwrap();
+
+        let config = Config {
+            import_paths: vec![root.to_path_buf()],
+            ..Config::default()
+        };
+        let paths = config.proto_paths(&file);
+
+        assert_eq!(paths, vec![root.to_path_buf()]);
+    }
+
+    #[test]
+    fn test_discover_roots_stops_at_max_depth_without_marker() {
+        let tmp = tempfile::tempdir().unwrap();
+        // No `.git`/`.capnp-root` anywhere in this tree, and it's deeper than
+        // `MAX_DISCOVER_DEPTH`, so the walk must give up rather than reaching `tmp`'s own parent
+        // (and beyond, all the way to the filesystem root).
+        let mut dir = tmp.path().to_path_buf();
+        for i in 0..MAX_DISCOVER_DEPTH + 4 {
+            dir = dir.join(format!("d{i}"));
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("foo.capnp");
+        std::fs::write(&file, "").unwrap();
+
+        let config = Config::default();
+        let paths = config.proto_paths(&file);
+
+        assert_eq!(paths.len(), MAX_DISCOVER_DEPTH);
+        assert!(
+            !paths.contains(&tmp.path().to_path_buf()),
+            "walk should have stopped before reaching the tempdir root: {paths:?}"
+        );
+    }
+}
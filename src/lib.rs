@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use lsp_server::{Connection, ExtractError, Message, Request, RequestId, Response};
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, CodeActionProviderCapability,
+    CodeActionResponse, Diagnostic, PublishDiagnosticsParams, Range, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+};
+
+pub mod capnp;
+pub mod config;
+pub mod report;
+
+use capnp::Suggestion;
+use config::Config;
+
+/// How long to wait after the last keystroke before compiling an unsaved buffer.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Fixes found for the diagnostics most recently published for each file, keyed by the range of
+/// the diagnostic they address. Shared with the threads that debounce `didChange` so they can
+/// publish straight from a background thread without round-tripping through the main loop.
+type SharedSuggestions = Arc<Mutex<HashMap<Url, HashMap<Range, Suggestion>>>>;
+
+/// The files last published for each document we compile (the document itself plus any imports
+/// `capnp` reported errors in), keyed by that document's `Url`. Diffed against each new compile
+/// so files that dropped out (because their error was fixed) get an empty diagnostics publish
+/// instead of being left with stale squiggles.
+type PublishedFiles = Arc<Mutex<HashMap<Url, HashSet<Url>>>>;
+
+/// Compiles `path` and prints its diagnostics as annotated source snippets to stderr, for the
+/// `capnls check` CLI subcommand. Returns whether the file compiled without errors.
+pub fn check(path: &std::path::Path) -> Result<bool> {
+    let abs_path = std::fs::canonicalize(path)
+        .with_context(|| format!("Failed to resolve {path:?}"))?;
+    let uri = Url::from_file_path(&abs_path)
+        .map_err(|()| anyhow::anyhow!("Failed to build a file URL from {abs_path:?}"))?;
+
+    let result = capnp::diags(&uri, &Config::default())?;
+    let has_errors = result
+        .diagnostics
+        .values()
+        .flatten()
+        .any(|d| d.severity != Some(lsp_types::DiagnosticSeverity::HINT));
+
+    report::print_diagnostics(&result)?;
+    Ok(!has_errors)
+}
+
+/// Runs the capnls language server to completion over `connection`.
+pub fn run(connection: Connection) -> Result<()> {
+    let server_capabilities = serde_json::to_value(ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        ..Default::default()
+    })?;
+    let initialize_params = connection.initialize(server_capabilities)?;
+    let params: lsp_types::InitializeParams = serde_json::from_value(initialize_params)?;
+
+    let config = params
+        .initialization_options
+        .map(serde_json::from_value)
+        .transpose()?
+        .unwrap_or_default();
+
+    let mut server = Server {
+        connection,
+        config,
+        documents: HashMap::new(),
+        doc_generations: Arc::new(Mutex::new(HashMap::new())),
+        suggestions: Arc::new(Mutex::new(HashMap::new())),
+        published: Arc::new(Mutex::new(HashMap::new())),
+    };
+    server.main_loop()
+}
+
+/// Per-session server state.
+struct Server {
+    connection: Connection,
+    config: Config,
+    /// The latest text of every open document, kept up to date by `didOpen`/`didChange` so we
+    /// can compile unsaved edits without re-reading the file from disk.
+    documents: HashMap<Url, String>,
+    /// The generation of the most recent `didChange` seen for each document. A debounced check
+    /// scheduled for an older generation bails out instead of publishing stale diagnostics.
+    doc_generations: Arc<Mutex<HashMap<Url, u64>>>,
+    suggestions: SharedSuggestions,
+    published: PublishedFiles,
+}
+
+impl Server {
+    fn main_loop(&mut self) -> Result<()> {
+        for msg in &self.connection.receiver {
+            match msg {
+                Message::Request(req) => {
+                    if self.connection.handle_shutdown(&req)? {
+                        return Ok(());
+                    }
+                    self.handle_request(req)?;
+                }
+                Message::Notification(not) => self.handle_notification(not)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, req: Request) -> Result<()> {
+        let req = match cast_request::<lsp_types::request::CodeActionRequest>(req) {
+            Ok((id, params)) => return self.handle_code_action(id, params),
+            Err(req) => req,
+        };
+        log::warn!("Unhandled request: {req:?}");
+        Ok(())
+    }
+
+    fn handle_notification(&mut self, not: lsp_server::Notification) -> Result<()> {
+        use lsp_types::notification::{
+            DidChangeConfiguration, DidChangeTextDocument, DidCloseTextDocument,
+            DidOpenTextDocument, DidSaveTextDocument, Notification,
+        };
+
+        match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(not.params)?;
+                let uri = params.text_document.uri;
+                self.documents.insert(uri.clone(), params.text_document.text.clone());
+                let result = capnp::diags_unsaved(&uri, &params.text_document.text, &self.config)?;
+                publish(
+                    &self.connection.sender,
+                    &self.suggestions,
+                    &self.published,
+                    &uri,
+                    result,
+                )?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams = serde_json::from_value(not.params)?;
+                let uri = params.text_document.uri;
+                // We advertise `TextDocumentSyncKind::FULL`, so there's exactly one change
+                // event and it carries the whole new document text.
+                if let Some(change) = params.content_changes.into_iter().next() {
+                    self.documents.insert(uri.clone(), change.text);
+                    self.schedule_debounced_check(uri);
+                }
+            }
+            DidSaveTextDocument::METHOD => {
+                let params: lsp_types::DidSaveTextDocumentParams = serde_json::from_value(not.params)?;
+                self.publish_diagnostics(&params.text_document.uri)?;
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(not.params)?;
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                self.doc_generations.lock().unwrap().remove(&uri);
+                self.clear_diagnostics(&uri)?;
+            }
+            DidChangeConfiguration::METHOD => {
+                let params: lsp_types::DidChangeConfigurationParams =
+                    serde_json::from_value(not.params)?;
+                self.config = serde_json::from_value(params.settings)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Compiles the on-disk file at `uri` and publishes diagnostics for it and any imported
+    /// files it reported errors in.
+    fn publish_diagnostics(&mut self, uri: &Url) -> Result<()> {
+        let result = capnp::diags(uri, &self.config)?;
+        publish(
+            &self.connection.sender,
+            &self.suggestions,
+            &self.published,
+            uri,
+            result,
+        )
+    }
+
+    /// Clears every diagnostic last published on behalf of `uri` (itself and any imports), e.g.
+    /// because the document was closed.
+    fn clear_diagnostics(&mut self, uri: &Url) -> Result<()> {
+        let files = self.published.lock().unwrap().remove(uri).unwrap_or_default();
+        {
+            let mut suggestions = self.suggestions.lock().unwrap();
+            for file in &files {
+                suggestions.remove(file);
+            }
+        }
+        for file in files {
+            self.connection
+                .sender
+                .send(Message::Notification(lsp_server::Notification::new(
+                    "textDocument/publishDiagnostics".into(),
+                    PublishDiagnosticsParams {
+                        uri: file,
+                        diagnostics: Vec::new(),
+                        version: None,
+                    },
+                )))?;
+        }
+        Ok(())
+    }
+
+    /// Schedules a debounced compile of `uri`'s current buffer text: after [`DEBOUNCE`]
+    /// elapses, if no newer `didChange` for the same document has arrived in the meantime, the
+    /// buffer is compiled and its diagnostics published directly from the background thread.
+    fn schedule_debounced_check(&mut self, uri: Url) {
+        let Some(text) = self.documents.get(&uri).cloned() else {
+            return;
+        };
+
+        let generation = {
+            let mut generations = self.doc_generations.lock().unwrap();
+            let generation = generations.entry(uri.clone()).or_insert(0);
+            *generation += 1;
+            *generation
+        };
+
+        let config = self.config.clone();
+        let sender = self.connection.sender.clone();
+        let generations = Arc::clone(&self.doc_generations);
+        let suggestions = Arc::clone(&self.suggestions);
+        let published = Arc::clone(&self.published);
+
+        std::thread::spawn(move || {
+            std::thread::sleep(DEBOUNCE);
+            if generations.lock().unwrap().get(&uri) != Some(&generation) {
+                // A newer edit superseded this one; let that check publish instead.
+                return;
+            }
+
+            match capnp::diags_unsaved(&uri, &text, &config) {
+                Ok(result) => {
+                    if let Err(e) = publish(&sender, &suggestions, &published, &uri, result) {
+                        log::warn!("Failed to publish diagnostics for {uri}: {e}");
+                    }
+                }
+                Err(e) => log::warn!("Failed to compile unsaved buffer {uri}: {e}"),
+            }
+        });
+    }
+
+    fn handle_code_action(&mut self, id: RequestId, params: CodeActionParams) -> Result<()> {
+        let uri = &params.text_document.uri;
+        let all_suggestions = self.suggestions.lock().unwrap();
+        let empty = HashMap::new();
+        let suggestions = all_suggestions.get(uri).unwrap_or(&empty);
+
+        let mut actions: CodeActionResponse = params
+            .context
+            .diagnostics
+            .iter()
+            .filter_map(|diag| {
+                let suggestion = suggestions.get(&diag.range)?;
+                Some(CodeActionOrCommand::CodeAction(code_action_for(
+                    uri,
+                    diag,
+                    suggestion,
+                )))
+            })
+            .collect();
+        if let Some(fix_all) = fix_all_action(uri, suggestions) {
+            actions.push(CodeActionOrCommand::CodeAction(fix_all));
+        }
+        drop(all_suggestions);
+
+        let result = serde_json::to_value(actions)?;
+        self.connection.sender.send(Message::Response(Response {
+            id,
+            result: Some(result),
+            error: None,
+        }))?;
+        Ok(())
+    }
+}
+
+/// Publishes every file's diagnostics in `result` and records its suggestions, so a code action
+/// request for any of those files can look its fixes back up. Free-standing (rather than a
+/// `Server` method) so the debounced `didChange` background thread can call it too.
+///
+/// `primary` is the document that was actually compiled (`uri` in `diags`/`diags_unsaved`). Its
+/// previous set of published files (itself plus any imports `capnp` reported errors in) is
+/// diffed against `result`, so a file that dropped out because its error got fixed is published
+/// an empty diagnostics list instead of being left with stale squiggles.
+///
+/// Takes the `crossbeam_channel::Sender` directly (the same channel type `Connection::sender`
+/// is) rather than a `&Connection`, so the debounce thread can send without a reference back
+/// into `Server`. Referencing `crossbeam_channel` by name like this needs it listed as a regular
+/// dependency, not just pulled in transitively through `lsp_server`.
+fn publish(
+    sender: &crossbeam_channel::Sender<Message>,
+    suggestions: &SharedSuggestions,
+    published: &PublishedFiles,
+    primary: &Url,
+    result: capnp::DiagResult,
+) -> Result<()> {
+    let new_files: HashSet<Url> = result.diagnostics.keys().cloned().collect();
+
+    for (file_uri, diagnostics) in result.diagnostics {
+        let file_suggestions = result.suggestions.get(&file_uri).cloned().unwrap_or_default();
+        suggestions
+            .lock()
+            .unwrap()
+            .insert(file_uri.clone(), file_suggestions);
+
+        let params = PublishDiagnosticsParams {
+            uri: file_uri,
+            diagnostics,
+            version: None,
+        };
+        sender.send(Message::Notification(lsp_server::Notification::new(
+            "textDocument/publishDiagnostics".into(),
+            params,
+        )))?;
+    }
+
+    let previous = published
+        .lock()
+        .unwrap()
+        .insert(primary.clone(), new_files.clone())
+        .unwrap_or_default();
+    for stale in previous.difference(&new_files) {
+        suggestions.lock().unwrap().remove(stale);
+        sender.send(Message::Notification(lsp_server::Notification::new(
+            "textDocument/publishDiagnostics".into(),
+            PublishDiagnosticsParams {
+                uri: stale.clone(),
+                diagnostics: Vec::new(),
+                version: None,
+            },
+        )))?;
+    }
+    Ok(())
+}
+
+/// Builds the `WorkspaceEdit` code action offered to fix `diag` via `suggestion`.
+fn code_action_for(uri: &Url, diag: &Diagnostic, suggestion: &Suggestion) -> CodeAction {
+    let edit = TextEdit {
+        range: suggestion.range,
+        new_text: suggestion.replacement.clone(),
+    };
+    let mut changes = HashMap::new();
+    changes.insert(uri.clone(), vec![edit]);
+
+    CodeAction {
+        title: format!("Fix: {}", diag.message),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diag.clone()]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..Default::default()
+        }),
+        is_preferred: Some(suggestion.applicability == capnp::Applicability::MachineApplicable),
+        ..Default::default()
+    }
+}
+
+/// Builds a `source.fixAll` action that applies every machine-applicable fix in `suggestions` at
+/// once, mirroring rustfix's "fix all in file": only `Applicability::MachineApplicable`
+/// suggestions are safe to bundle this way, so `MaybeIncorrect` ones (e.g. ordinal renumbering)
+/// are left for the user to apply individually. Returns `None` if there's nothing to fix.
+fn fix_all_action(uri: &Url, suggestions: &HashMap<Range, Suggestion>) -> Option<CodeAction> {
+    let edits: Vec<TextEdit> = suggestions
+        .values()
+        .filter(|s| s.applicability == capnp::Applicability::MachineApplicable)
+        .map(|s| TextEdit {
+            range: s.range,
+            new_text: s.replacement.clone(),
+        })
+        .collect();
+    if edits.is_empty() {
+        return None;
+    }
+
+    let mut changes = HashMap::new();
+    changes.insert(uri.clone(), edits);
+
+    Some(CodeAction {
+        title: "Fix all auto-fixable problems in file".to_string(),
+        kind: Some(CodeActionKind::SOURCE_FIX_ALL),
+        edit: Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+fn cast_request<R>(req: Request) -> Result<(RequestId, R::Params), Request>
+where
+    R: lsp_types::request::Request,
+    R::Params: serde::de::DeserializeOwned,
+{
+    match req.extract::<R::Params>(R::METHOD) {
+        Ok(ok) => Ok(ok),
+        Err(ExtractError::MethodMismatch(req)) => Err(req),
+        Err(ExtractError::JsonError { method, error }) => {
+            panic!("Failed to parse {method} request: {error}")
+        }
+    }
+}
@@ -1,7 +1,27 @@
-fn main() -> anyhow::Result<()> {
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+fn main() -> Result<()> {
     env_logger::init();
-    let (connection, io_threads) = lsp_server::Connection::stdio();
-    capnls::run(connection)?;
-    io_threads.join()?;
+
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("check") => {
+            let file: PathBuf = args
+                .next()
+                .context("Usage: capnls check <file.capnp>")?
+                .into();
+            if !capnls::check(&file)? {
+                std::process::exit(1);
+            }
+        }
+        Some(other) => bail!("Unknown subcommand: {other}"),
+        None => {
+            let (connection, io_threads) = lsp_server::Connection::stdio();
+            capnls::run(connection)?;
+            io_threads.join()?;
+        }
+    }
     Ok(())
 }
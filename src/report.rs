@@ -0,0 +1,74 @@
+use codespan_reporting::diagnostic::{Diagnostic as CsDiagnostic, Label, Severity};
+use codespan_reporting::files::SimpleFiles;
+use codespan_reporting::term::{
+    self,
+    termcolor::{ColorChoice, StandardStream},
+};
+use lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range};
+
+use crate::capnp::DiagResult;
+
+/// Prints `result`'s diagnostics as annotated source snippets: the offending line, a caret
+/// underline spanning the diagnostic's range, the severity and the message, with secondary
+/// spans for anything folded into `related_information`. Used by the `check` subcommand so the
+/// crate can double as a CI linter without an editor.
+///
+/// Runs as part of the normal `check` subcommand, so `codespan-reporting` needs to be a regular
+/// dependency rather than a dev-dependency.
+pub fn print_diagnostics(result: &DiagResult) -> anyhow::Result<()> {
+    let writer = StandardStream::stderr(ColorChoice::Auto);
+    let term_config = term::Config::default();
+
+    for (uri, diagnostics) in &result.diagnostics {
+        let Ok(path) = uri.to_file_path() else {
+            continue;
+        };
+        let source = std::fs::read_to_string(&path)?;
+
+        let mut files = SimpleFiles::new();
+        let file_id = files.add(path.display().to_string(), source.as_str());
+
+        for diag in diagnostics {
+            let cs_diag = to_codespan(file_id, &source, diag);
+            term::emit(&mut writer.lock(), &term_config, &files, &cs_diag)?;
+        }
+    }
+    Ok(())
+}
+
+fn to_codespan(file_id: usize, source: &str, diag: &Diagnostic) -> CsDiagnostic<usize> {
+    let severity = match diag.severity {
+        Some(DiagnosticSeverity::WARNING) => Severity::Warning,
+        Some(DiagnosticSeverity::HINT) => Severity::Help,
+        Some(DiagnosticSeverity::INFORMATION) => Severity::Note,
+        _ => Severity::Error,
+    };
+
+    let mut labels = vec![Label::primary(file_id, range_to_span(source, diag.range))];
+    for related in diag.related_information.iter().flatten() {
+        labels.push(
+            Label::secondary(file_id, range_to_span(source, related.location.range))
+                .with_message(related.message.clone()),
+        );
+    }
+
+    CsDiagnostic::new(severity)
+        .with_message(diag.message.clone())
+        .with_labels(labels)
+}
+
+/// Converts an LSP `Range` to the byte span `codespan_reporting` wants.
+fn range_to_span(source: &str, range: Range) -> std::ops::Range<usize> {
+    position_to_offset(source, range.start)..position_to_offset(source, range.end)
+}
+
+fn position_to_offset(source: &str, pos: Position) -> usize {
+    let mut offset = 0;
+    for (i, line) in source.split_inclusive('\n').enumerate() {
+        if i as u32 == pos.line {
+            return offset + (pos.character as usize).min(line.len());
+        }
+        offset += line.len();
+    }
+    offset
+}